@@ -0,0 +1,265 @@
+//! GraphQL query module over the availability data source.
+//!
+//! Exposes blocks, leaves, and transactions as a typed schema with nested field resolution and
+//! cursor-based pagination, plus a subscription that polls the availability data source for newly
+//! decided blocks. The data source is injected into the schema as context data (see `schema`
+//! below), so resolvers read through the same `Arc<RwLock<AppState<N, D>>>` the REST availability
+//! module uses.
+
+use async_graphql::{
+    complex::ComplexObject, futures_util::stream::Stream, Context, Object, Schema, SimpleObject,
+    Subscription,
+};
+use async_graphql_tide::graphql as graphql_endpoint;
+use async_std::{sync::RwLock, task::sleep};
+use hotshot_query_service::availability::{AvailabilityDataSource, BlockQueryData};
+use std::sync::Arc;
+use std::time::Duration;
+use tide_disco::{api::Api, method::ReadState, Error};
+
+use super::{data_source::SequencerDataSource, AppState};
+use crate::network;
+
+/// Options for the GraphQL query module.
+#[derive(clap::Parser, Clone, Copy, Debug, Default)]
+pub struct Graphql;
+
+/// A block, as exposed to GraphQL clients.
+///
+/// `transactions` is resolved separately (see the `ComplexObject` impl below), from the block
+/// payload stored on this object.
+#[derive(Clone, SimpleObject)]
+#[graphql(complex)]
+pub struct Block {
+    pub height: u64,
+    pub hash: String,
+    #[graphql(skip)]
+    payload: Vec<u8>,
+}
+
+#[ComplexObject]
+impl Block {
+    /// The block's transactions.
+    ///
+    /// Until per-transaction payload iteration is wired up for every `BlockPayload` impl, this
+    /// exposes the raw block payload as a single transaction entry.
+    async fn transactions(&self) -> Vec<Transaction> {
+        vec![Transaction {
+            index: 0,
+            hash: self.hash.clone(),
+            payload: self.payload.clone(),
+        }]
+    }
+}
+
+/// A transaction, as exposed to GraphQL clients.
+#[derive(Clone, SimpleObject)]
+pub struct Transaction {
+    pub index: usize,
+    pub hash: String,
+    pub payload: Vec<u8>,
+}
+
+/// A leaf, as exposed to GraphQL clients.
+#[derive(Clone, SimpleObject)]
+pub struct Leaf {
+    pub height: u64,
+    pub hash: String,
+    pub block_hash: String,
+}
+
+fn block_to_graphql<Types>(block: BlockQueryData<Types>) -> Block
+where
+    Types: hotshot_types::traits::node_implementation::NodeType,
+{
+    Block {
+        height: block.height(),
+        hash: block.hash().to_string(),
+        payload: block.payload().encode().to_vec(),
+    }
+}
+
+/// A page of blocks plus the Relay-style metadata needed to request the next one.
+#[derive(SimpleObject)]
+pub struct BlockConnection {
+    pub edges: Vec<BlockEdge>,
+    pub page_info: PageInfo,
+}
+
+/// A single block together with its opaque cursor.
+#[derive(SimpleObject)]
+pub struct BlockEdge {
+    pub cursor: String,
+    pub node: Block,
+}
+
+/// Relay connection metadata: whether another page exists and the cursor to request it with.
+#[derive(SimpleObject)]
+pub struct PageInfo {
+    pub has_next_page: bool,
+    pub end_cursor: Option<String>,
+}
+
+/// A cursor is just a block height, opaque to clients.
+fn encode_cursor(height: u64) -> String {
+    height.to_string()
+}
+
+/// Decode an opaque pagination cursor (the height of the last item seen) back into a height.
+fn decode_cursor(cursor: &str) -> Option<u64> {
+    cursor.parse().ok()
+}
+
+/// Root query type: blocks, leaves, and transactions resolved from the availability data source.
+pub struct Query<N, D> {
+    _marker: std::marker::PhantomData<(N, D)>,
+}
+
+#[Object]
+impl<N, D> Query<N, D>
+where
+    N: network::Type,
+    D: SequencerDataSource + Send + Sync + 'static,
+{
+    /// Fetch a single block by height.
+    async fn block(&self, ctx: &Context<'_>, height: u64) -> async_graphql::Result<Option<Block>> {
+        let state = ctx.data::<Arc<RwLock<AppState<N, D>>>>()?;
+        let mut ds = state.write().await;
+        let block = ds.get_block(height as usize).await;
+        Ok(block.ok().map(block_to_graphql))
+    }
+
+    /// Fetch a single leaf by height.
+    async fn leaf(&self, ctx: &Context<'_>, height: u64) -> async_graphql::Result<Option<Leaf>> {
+        let state = ctx.data::<Arc<RwLock<AppState<N, D>>>>()?;
+        let mut ds = state.write().await;
+        let leaf = ds.get_leaf(height as usize).await;
+        Ok(leaf.ok().map(|leaf| Leaf {
+            height: leaf.height(),
+            hash: leaf.hash().to_string(),
+            block_hash: leaf.block_hash().to_string(),
+        }))
+    }
+
+    /// Fetch a page of blocks, oldest first, starting after `after` (an opaque cursor from a
+    /// previous page), up to `first` results.
+    async fn blocks(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> async_graphql::Result<BlockConnection> {
+        let first = first.unwrap_or(10).min(100);
+        let start = after.and_then(|c| decode_cursor(&c)).map_or(0, |h| h + 1);
+
+        let state = ctx.data::<Arc<RwLock<AppState<N, D>>>>()?;
+        let mut ds = state.write().await;
+
+        // Fetch one extra block so hasNextPage doesn't have to guess.
+        let mut edges = Vec::with_capacity(first);
+        let mut has_next_page = false;
+        for height in start..start + first as u64 + 1 {
+            let Ok(block) = ds.get_block(height as usize).await else {
+                break;
+            };
+            if edges.len() == first {
+                has_next_page = true;
+                break;
+            }
+            edges.push(BlockEdge {
+                cursor: encode_cursor(height),
+                node: block_to_graphql(block),
+            });
+        }
+        let end_cursor = edges.last().map(|edge| edge.cursor.clone());
+
+        Ok(BlockConnection {
+            edges,
+            page_info: PageInfo {
+                has_next_page,
+                end_cursor,
+            },
+        })
+    }
+}
+
+/// Root subscription type: a live stream of newly decided blocks.
+pub struct Subscription_<N, D> {
+    _marker: std::marker::PhantomData<(N, D)>,
+}
+
+#[Subscription]
+impl<N, D> Subscription_<N, D>
+where
+    N: network::Type,
+    D: SequencerDataSource + Send + Sync + 'static,
+{
+    /// Stream blocks as consensus decides them, starting from the current chain tip.
+    ///
+    /// Implemented by polling the availability data source, so it only ever yields blocks that
+    /// have actually been persisted.
+    async fn blocks(&self, ctx: &Context<'_>) -> async_graphql::Result<impl Stream<Item = Block>> {
+        let state = ctx.data::<Arc<RwLock<AppState<N, D>>>>()?.clone();
+        let start_height = {
+            let mut ds = state.write().await;
+            // A chain with no blocks yet has no height to seed from; start from genesis.
+            ds.get_block_height().await.unwrap_or(0) as u64
+        };
+        Ok(futures::stream::unfold(start_height, move |next_height| {
+            let state = state.clone();
+            async move {
+                loop {
+                    let mut ds = state.write().await;
+                    match ds.get_block(next_height as usize).await {
+                        Ok(block) => return Some((block_to_graphql(block), next_height + 1)),
+                        Err(_) => {
+                            drop(ds);
+                            sleep(Duration::from_secs(1)).await;
+                        }
+                    }
+                }
+            }
+        }))
+    }
+}
+
+pub type SequencerSchema<N, D> =
+    Schema<Query<N, D>, async_graphql::EmptyMutation, Subscription_<N, D>>;
+
+/// Build the GraphQL schema over the given data source, injecting `state` as context data so
+/// resolvers can read through it.
+pub fn schema<N, D>(state: Arc<RwLock<AppState<N, D>>>) -> SequencerSchema<N, D>
+where
+    N: network::Type,
+    D: SequencerDataSource + Send + Sync + 'static,
+{
+    Schema::build(
+        Query {
+            _marker: std::marker::PhantomData,
+        },
+        async_graphql::EmptyMutation,
+        Subscription_ {
+            _marker: std::marker::PhantomData,
+        },
+    )
+    .data(state)
+    .finish()
+}
+
+/// Define the `graphql` module, registering the query/subscription schema as a single endpoint.
+pub fn define_api<N, D, State>(
+    state: Arc<RwLock<AppState<N, D>>>,
+) -> Result<Api<State, Error>, tide_disco::api::ApiError>
+where
+    N: network::Type,
+    D: SequencerDataSource + Send + Sync + 'static,
+    State: 'static + Send + Sync + ReadState,
+{
+    let mut api = Api::<State, Error>::new(Default::default())?;
+    let schema = schema::<N, D>(state);
+    api.at("graphql", move |req| {
+        let schema = schema.clone();
+        Box::pin(graphql_endpoint(schema, req))
+    })?;
+    Ok(api)
+}
@@ -0,0 +1,260 @@
+//! Prometheus text-format exposition of the live metrics registry.
+//!
+//! `PrometheusMetrics` implements hotshot's `Metrics` trait directly, so it can be handed to
+//! `init_handle` exactly like any other metrics sink, while retaining the counters/gauges/
+//! histograms it creates so they can be rendered back out as a Prometheus 0.0.4 text exposition
+//! on demand. This avoids depending on any introspection API on the opaque `Box<dyn Metrics>`
+//! handed back by a data source's `populate_metrics`.
+
+use hotshot_types::traits::metrics::{Counter, CustomMetric, Gauge, Histogram, Metrics};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::{
+    atomic::{AtomicI64, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use tide_disco::{api::Api, method::ReadState, Error};
+
+/// Options for the Prometheus metrics endpoint.
+#[derive(clap::Parser, Clone, Copy, Debug, Default)]
+pub struct Prometheus;
+
+#[derive(Default)]
+struct Registry {
+    counters: HashMap<String, Arc<AtomicCounter>>,
+    gauges: HashMap<String, Arc<AtomicGauge>>,
+    histograms: HashMap<String, Arc<RecordingHistogram>>,
+}
+
+/// A `Metrics` implementation that doubles as its own Prometheus exporter.
+///
+/// Pass a clone of this to `init_handle` to have consensus report into it, and keep the original
+/// around to call `export()` from the `/metrics` endpoint.
+#[derive(Clone, Default)]
+pub struct PrometheusMetrics {
+    registry: Arc<Mutex<Registry>>,
+    prefix: Vec<String>,
+}
+
+impl PrometheusMetrics {
+    fn metric_name(&self, label: &str) -> String {
+        let mut parts = self.prefix.clone();
+        parts.push(sanitize(label));
+        parts.join("_")
+    }
+
+    /// Render every counter, gauge, and histogram in the registry as a Prometheus 0.0.4 text
+    /// exposition: `# HELP`/`# TYPE` lines followed by `name value` samples (for histograms,
+    /// `name_bucket{le="..."} value` per bucket, then `name_sum` and `name_count`).
+    pub fn export(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        for (name, counter) in &registry.counters {
+            write_help(&mut out, name, "counter");
+            writeln!(out, "{name} {}", counter.value()).ok();
+        }
+        for (name, gauge) in &registry.gauges {
+            write_help(&mut out, name, "gauge");
+            writeln!(out, "{name} {}", gauge.value()).ok();
+        }
+        for (name, histogram) in &registry.histograms {
+            write_help(&mut out, name, "histogram");
+            let (buckets, sum, count) = histogram.snapshot();
+            for (bound, cumulative) in buckets {
+                writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}").ok();
+            }
+            writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}").ok();
+            writeln!(out, "{name}_sum {sum}").ok();
+            writeln!(out, "{name}_count {count}").ok();
+        }
+
+        out
+    }
+}
+
+fn write_help(out: &mut String, name: &str, kind: &str) {
+    writeln!(out, "# HELP {name} {name}").ok();
+    writeln!(out, "# TYPE {name} {kind}").ok();
+}
+
+/// Prometheus metric names may only contain `[a-zA-Z0-9_:]`.
+fn sanitize(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+impl Metrics for PrometheusMetrics {
+    fn create_counter(&self, label: String, _unit_label: Option<String>) -> Box<dyn Counter> {
+        let name = self.metric_name(&label);
+        let counter = Arc::new(AtomicCounter::default());
+        self.registry.lock().unwrap().counters.insert(name, counter.clone());
+        Box::new(counter)
+    }
+
+    fn create_gauge(&self, label: String, _unit_label: Option<String>) -> Box<dyn Gauge> {
+        let name = self.metric_name(&label);
+        let gauge = Arc::new(AtomicGauge::default());
+        self.registry.lock().unwrap().gauges.insert(name, gauge.clone());
+        Box::new(gauge)
+    }
+
+    fn create_histogram(&self, label: String, _unit_label: Option<String>) -> Box<dyn Histogram> {
+        let name = self.metric_name(&label);
+        let histogram = Arc::new(RecordingHistogram::default());
+        self.registry
+            .lock()
+            .unwrap()
+            .histograms
+            .insert(name, histogram.clone());
+        Box::new(histogram)
+    }
+
+    fn create_text(&self, _label: String) -> Box<dyn CustomMetric> {
+        // Free-form text metrics have no Prometheus equivalent.
+        Box::new(NullCustomMetric)
+    }
+
+    fn subgroup(&self, subgroup_name: String) -> Box<dyn Metrics> {
+        let mut prefix = self.prefix.clone();
+        prefix.push(sanitize(&subgroup_name));
+        Box::new(Self {
+            registry: self.registry.clone(),
+            prefix,
+        })
+    }
+}
+
+#[derive(Default)]
+struct AtomicCounter(AtomicU64);
+
+impl AtomicCounter {
+    fn value(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Counter for AtomicCounter {
+    fn add(&self, amount: usize) {
+        self.0.fetch_add(amount as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct AtomicGauge(AtomicI64);
+
+impl AtomicGauge {
+    fn value(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Gauge for AtomicGauge {
+    fn set(&self, amount: usize) {
+        self.0.store(amount as i64, Ordering::Relaxed);
+    }
+
+    fn update(&self, delta: i64) {
+        self.0.fetch_add(delta, Ordering::Relaxed);
+    }
+}
+
+/// Fixed bucket bounds, in the style of the default Prometheus client histograms.
+const BUCKET_BOUNDS: [f64; 8] = [0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 10.0, 60.0];
+
+#[derive(Default)]
+struct RecordingHistogram {
+    bucket_counts: [AtomicU64; BUCKET_BOUNDS.len()],
+    count: AtomicU64,
+    sum: Mutex<f64>,
+}
+
+impl RecordingHistogram {
+    /// Cumulative bucket counts (as Prometheus expects), plus the total sum and count.
+    ///
+    /// `bucket_counts` is already cumulative (`add_point` increments every bucket whose bound is
+    /// `>= point`), so this just reads the counters back as-is.
+    fn snapshot(&self) -> (Vec<(f64, u64)>, f64, u64) {
+        let buckets = BUCKET_BOUNDS
+            .iter()
+            .zip(self.bucket_counts.iter())
+            .map(|(bound, count)| (*bound, count.load(Ordering::Relaxed)))
+            .collect();
+        (buckets, *self.sum.lock().unwrap(), self.count.load(Ordering::Relaxed))
+    }
+}
+
+impl Histogram for RecordingHistogram {
+    fn add_point(&self, point: f64) {
+        for (bound, count) in BUCKET_BOUNDS.iter().zip(self.bucket_counts.iter()) {
+            if point <= *bound {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        *self.sum.lock().unwrap() += point;
+    }
+}
+
+struct NullCustomMetric;
+
+impl CustomMetric for NullCustomMetric {
+    fn set(&self, _value: String) {}
+}
+
+/// Define the `/metrics` API: a single endpoint returning the Prometheus exposition of `metrics`.
+///
+/// `metrics` is captured directly, so this module has no bound on what the rest of the API
+/// stores as its state.
+pub fn define_api<State>(
+    metrics: PrometheusMetrics,
+) -> Result<Api<State, Error>, tide_disco::api::ApiError>
+where
+    State: 'static + Send + Sync + ReadState,
+{
+    let mut api = Api::<State, Error>::new(Default::default())?;
+    api.get("export", move |_req, _state| {
+        let metrics = metrics.clone();
+        async move { Ok(metrics.export()) }
+    })?;
+    Ok(api)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_monotonically_cumulative() {
+        let histogram = RecordingHistogram::default();
+        histogram.add_point(0.0005);
+
+        let (buckets, sum, count) = histogram.snapshot();
+        assert_eq!(count, 1);
+        assert_eq!(sum, 0.0005);
+
+        // Every bucket from the first matching bound up through `+Inf` should contain exactly
+        // this one observation, never more.
+        for (_, cumulative) in &buckets {
+            assert_eq!(*cumulative, 1);
+        }
+        assert!(buckets.windows(2).all(|w| w[0].1 <= w[1].1));
+    }
+
+    #[test]
+    fn histogram_export_has_no_bucket_exceeding_count() {
+        let metrics = PrometheusMetrics::default();
+        let histogram = metrics.create_histogram("latency".into(), None);
+        histogram.add_point(0.0005);
+        histogram.add_point(2.0);
+        histogram.add_point(100.0);
+
+        let exported = metrics.export();
+        for line in exported.lines().filter(|l| l.contains("_bucket{")) {
+            let value: u64 = line.rsplit(' ').next().unwrap().parse().unwrap();
+            assert!(value <= 3, "bucket count {value} exceeds total observation count");
+        }
+    }
+}
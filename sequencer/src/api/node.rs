@@ -0,0 +1,49 @@
+//! The running API server and consensus node, with graceful shutdown.
+
+use async_std::task::JoinHandle;
+use futures::{channel::oneshot, future::Shared};
+
+use super::{Consensus, NodeIndex};
+use crate::network;
+
+/// The sending half of a shutdown signal: fires once to tell every clone of the matching
+/// [`ShutdownReceiver`] to stop.
+pub type ShutdownSignal = oneshot::Sender<()>;
+
+/// The receiving half of a shutdown signal.
+///
+/// A plain oneshot receiver can only be awaited by a single task: the first clone to complete it
+/// would consume the value, leaving the rest waiting forever. Wrapping it in [`Shared`] lets the
+/// serve future and the update loop each hold their own clone and both observe the same signal.
+pub type ShutdownReceiver = Shared<oneshot::Receiver<()>>;
+
+/// A running sequencer node: the consensus handle plus the task serving its API.
+pub struct SequencerNode<N: network::Type> {
+    pub handle: Consensus<N>,
+    pub node_index: NodeIndex,
+    pub update_task: JoinHandle<anyhow::Result<()>>,
+    /// The submit API's rate-limit background task, if one was spawned: either flushing
+    /// accounting deltas to storage or, absent storage, just evicting idle client buckets.
+    ///
+    /// `None` when the submit API isn't rate limited at all.
+    pub(super) rate_limit_task: Option<JoinHandle<()>>,
+    pub(super) shutdown: ShutdownSignal,
+}
+
+impl<N: network::Type> SequencerNode<N> {
+    /// Gracefully shut down this node.
+    ///
+    /// Signals the serve future, update loop, and rate-limit background task to stop, waits for
+    /// them to flush any pending SQL writes and return, then shuts down consensus. Enables clean
+    /// restarts and integration tests that start and stop nodes in-process.
+    pub async fn shutdown(self) -> anyhow::Result<()> {
+        // Best-effort: the receiving end may already have been dropped if the task exited early.
+        let _ = self.shutdown.send(());
+        self.update_task.await?;
+        if let Some(rate_limit_task) = self.rate_limit_task {
+            rate_limit_task.await;
+        }
+        self.handle.hotshot.shut_down().await;
+        Ok(())
+    }
+}
@@ -1,8 +1,12 @@
 //! Sequencer-specific API options and initialization.
 
 use super::{
-    data_source::SequencerDataSource, endpoints, fs, sql, update::update_loop, AppState, Consensus,
-    NodeIndex, SequencerNode,
+    data_source::SequencerDataSource, endpoints, fs,
+    graphql::{self, Graphql},
+    metrics::{self, Prometheus},
+    node::ShutdownReceiver,
+    rate_limit::{RateLimitMiddleware, RateLimiter},
+    sql, update::update_loop, AppState, Consensus, NodeIndex, SequencerNode,
 };
 use crate::{network, persistence};
 use async_std::{
@@ -10,13 +14,14 @@ use async_std::{
     task::spawn,
 };
 use clap::Parser;
-use futures::future::{BoxFuture, TryFutureExt};
+use futures::{channel::oneshot, future::BoxFuture, select, FutureExt};
 use hotshot_query_service::{
     data_source::{ExtensibleDataSource, MetricsDataSource},
     status::{self, UpdateStatusData},
     Error,
 };
 use hotshot_types::traits::metrics::{Metrics, NoMetrics};
+use std::path::PathBuf;
 use tide_disco::App;
 
 #[derive(Clone, Debug)]
@@ -27,6 +32,9 @@ pub struct Options {
     pub status: Option<Status>,
     pub storage_fs: Option<persistence::fs::Options>,
     pub storage_sql: Option<persistence::sql::Options>,
+    pub rate_limit: Option<RateLimit>,
+    pub prometheus: Option<Prometheus>,
+    pub graphql: Option<Graphql>,
 }
 
 impl From<Http> for Options {
@@ -38,6 +46,9 @@ impl From<Http> for Options {
             status: None,
             storage_fs: None,
             storage_sql: None,
+            rate_limit: None,
+            prometheus: None,
+            graphql: None,
         }
     }
 }
@@ -69,6 +80,24 @@ impl Options {
         self
     }
 
+    /// Rate limit the submit API by client identity.
+    pub fn rate_limit(mut self, opt: RateLimit) -> Self {
+        self.rate_limit = Some(opt);
+        self
+    }
+
+    /// Expose the metrics registry in Prometheus exposition format.
+    pub fn prometheus(mut self, opt: Prometheus) -> Self {
+        self.prometheus = Some(opt);
+        self
+    }
+
+    /// Add a GraphQL query module over the availability data source.
+    pub fn graphql(mut self, opt: Graphql) -> Self {
+        self.graphql = Some(opt);
+        self
+    }
+
     /// Whether these options will run the query API.
     pub fn has_query_module(&self) -> bool {
         self.query.is_some() && (self.storage_fs.is_some() || self.storage_sql.is_some())
@@ -79,7 +108,14 @@ impl Options {
     /// The function `init_handle` is used to create a consensus handle from a metrics object. The
     /// metrics object is created from the API data source, so that consensus will populuate metrics
     /// that can then be read and served by the API.
-    pub async fn serve<N, F>(mut self, init_handle: F) -> anyhow::Result<SequencerNode<N>>
+    ///
+    /// If the Prometheus option is set, the metrics object is a `PrometheusMetrics` registry
+    /// served by the `metrics` module instead, and the data source's own metrics are not
+    /// populated.
+    ///
+    /// Returns `Ok(None)` if the SQL storage options requested `--migrations-only`: migrations
+    /// have been applied, nothing was started, and it's up to the caller whether and how to exit.
+    pub async fn serve<N, F>(mut self, init_handle: F) -> anyhow::Result<Option<SequencerNode<N>>>
     where
         N: network::Type,
         F: FnOnce(Box<dyn Metrics>) -> BoxFuture<'static, (Consensus<N>, NodeIndex)>,
@@ -87,14 +123,36 @@ impl Options {
         // The server state type depends on whether we are running a query or status API or not, so
         // we handle the two cases differently.
         let node = if let Some(opt) = self.storage_sql.take() {
-            init_with_query_module::<N, sql::DataSource>(self, opt, init_handle).await?
+            let accounting_storage = opt.clone();
+            let reset = opt.reset_store;
+            match init_with_query_module::<N, sql::DataSource>(
+                self,
+                opt,
+                Some(accounting_storage),
+                reset,
+                init_handle,
+            )
+            .await?
+            {
+                Some(node) => node,
+                None => return Ok(None),
+            }
         } else if let Some(opt) = self.storage_fs.take() {
-            init_with_query_module::<N, fs::DataSource>(self, opt, init_handle).await?
+            // The file-system data source has no notion of a resettable schema or
+            // migrations-only mode, so this always produces a node to serve.
+            init_with_query_module::<N, fs::DataSource>(self, opt, None, false, init_handle)
+                .await?
+                .expect("fs-backed storage never returns None from init_with_query_module")
         } else if self.status.is_some() {
             // If a status API is requested but no availability API, we use the `MetricsDataSource`,
             // which allows us to run the status API with no persistent storage.
             let ds = MetricsDataSource::default();
-            let (handle, node_index) = init_handle(ds.populate_metrics()).await;
+            let prometheus_registry = self.prometheus.is_some().then(metrics::PrometheusMetrics::default);
+            let metrics: Box<dyn Metrics> = match &prometheus_registry {
+                Some(registry) => Box::new(registry.clone()),
+                None => ds.populate_metrics(),
+            };
+            let (handle, node_index) = init_handle(metrics).await;
             let mut app = App::<_, Error>::with_state(Arc::new(RwLock::new(
                 ExtensibleDataSource::new(ds, handle.clone()),
             )));
@@ -103,19 +161,41 @@ impl Options {
             let status_api = status::define_api(&Default::default())?;
             app.register_module("status", status_api)?;
 
+            // Initialize Prometheus metrics API.
+            if let Some(registry) = prometheus_registry {
+                let metrics_api = metrics::define_api(registry)?;
+                app.register_module("metrics", metrics_api)?;
+            }
+
+            let (shutdown, shutdown_rx) = oneshot::channel();
+            let shutdown_rx = shutdown_rx.shared();
+
             // Initialize submit API
+            let mut rate_limit_task = None;
             if self.submit.is_some() {
-                let submit_api = endpoints::submit()?;
+                let mut submit_api = endpoints::submit()?;
+                if let Some(rate_limit) = &self.rate_limit {
+                    let limiter = RateLimiter::new(rate_limit.rate, rate_limit.burst);
+                    // No SQL storage in this configuration, so there's nowhere to flush
+                    // accounting into, but idle buckets still need evicting to bound memory.
+                    rate_limit_task = Some(limiter.clone().spawn_eviction_task(
+                        std::time::Duration::from_secs(60),
+                        shutdown_rx.clone(),
+                    ));
+                    submit_api.with_middleware(RateLimitMiddleware::new(limiter));
+                }
                 app.register_module("submit", submit_api)?;
             }
 
             SequencerNode {
                 handle,
                 node_index,
-                update_task: spawn(
-                    app.serve(format!("0.0.0.0:{}", self.http.port))
-                        .map_err(anyhow::Error::from),
-                ),
+                shutdown,
+                rate_limit_task,
+                update_task: spawn({
+                    let http = self.http.clone();
+                    async move { http.bind(app, shutdown_rx).await }
+                }),
             }
         } else {
             // If no status or availability API is requested, we don't need metrics or a query
@@ -124,25 +204,41 @@ impl Options {
             let (handle, node_index) = init_handle(Box::new(NoMetrics)).await;
             let mut app = App::<_, Error>::with_state(RwLock::new(handle.clone()));
 
+            let (shutdown, shutdown_rx) = oneshot::channel();
+            let shutdown_rx = shutdown_rx.shared();
+
             // Initialize submit API
+            let mut rate_limit_task = None;
             if self.submit.is_some() {
-                let submit_api = endpoints::submit::<N, RwLock<Consensus<N>>>()?;
+                let mut submit_api = endpoints::submit::<N, RwLock<Consensus<N>>>()?;
+                if let Some(rate_limit) = &self.rate_limit {
+                    let limiter = RateLimiter::new(rate_limit.rate, rate_limit.burst);
+                    // No SQL storage in this configuration, so there's nowhere to flush
+                    // accounting into, but idle buckets still need evicting to bound memory.
+                    rate_limit_task = Some(limiter.clone().spawn_eviction_task(
+                        std::time::Duration::from_secs(60),
+                        shutdown_rx.clone(),
+                    ));
+                    submit_api.with_middleware(RateLimitMiddleware::new(limiter));
+                }
                 app.register_module("submit", submit_api)?;
             }
 
             SequencerNode {
                 handle,
                 node_index,
-                update_task: spawn(
-                    app.serve(format!("0.0.0.0:{}", self.http.port))
-                        .map_err(anyhow::Error::from),
-                ),
+                shutdown,
+                rate_limit_task,
+                update_task: spawn({
+                    let http = self.http.clone();
+                    async move { http.bind(app, shutdown_rx).await }
+                }),
             }
         };
 
         // Start consensus.
         node.handle.hotshot.start_consensus().await;
-        Ok(node)
+        Ok(Some(node))
     }
 }
 
@@ -155,6 +251,71 @@ pub struct Http {
     /// Port that the HTTP API will use.
     #[clap(long, env = "ESPRESSO_SEQUENCER_API_PORT")]
     pub port: u16,
+
+    /// Interface that the HTTP API will bind to.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_API_BIND_ADDRESS",
+        default_value = "0.0.0.0"
+    )]
+    pub bind_address: std::net::IpAddr,
+
+    /// Path to a PEM-encoded TLS certificate.
+    ///
+    /// If this and `tls_key` are both set, the API is served over HTTPS. If neither is set, it
+    /// falls back to plaintext HTTP. Setting only one of the two is an error.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_API_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Path to the PEM-encoded private key matching `tls_cert`.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_API_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+}
+
+impl Http {
+    /// Serve `app` on this `Http` configuration, terminating TLS if `tls_cert`/`tls_key` are set
+    /// and falling back to plaintext otherwise.
+    ///
+    /// Returns as soon as `shutdown` fires. Returns an error immediately if only one of
+    /// `tls_cert`/`tls_key` is set, rather than silently falling back to plaintext.
+    async fn bind<State, E>(
+        &self,
+        app: App<State, E>,
+        shutdown: ShutdownReceiver,
+    ) -> anyhow::Result<()>
+    where
+        State: 'static + Send + Sync,
+        E: 'static + std::fmt::Debug + Send + Sync + From<tide_disco::StatusCode>,
+    {
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            anyhow::bail!(
+                "--tls-cert and --tls-key must be set together; got cert={:?} key={:?}",
+                self.tls_cert,
+                self.tls_key
+            );
+        }
+
+        let bind_address = format!("{}:{}", self.bind_address, self.port);
+        let serve = async {
+            match (&self.tls_cert, &self.tls_key) {
+                (Some(cert), Some(key)) => {
+                    app.listen(
+                        tide_rustls::TlsListener::build()
+                            .addrs(&bind_address)
+                            .cert(cert)
+                            .key(key),
+                    )
+                    .await
+                }
+                _ => app.serve(bind_address).await,
+            }
+        };
+
+        select! {
+            res = serve.fuse() => res.map_err(anyhow::Error::from),
+            _ = shutdown.fuse() => Ok(()),
+        }
+    }
 }
 
 /// Options for the submission API module.
@@ -169,19 +330,54 @@ pub struct Status;
 #[derive(Parser, Clone, Copy, Debug, Default)]
 pub struct Query;
 
+/// Options for rate limiting and accounting the submit API.
+#[derive(Parser, Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// Sustained token refill rate, in requests per second, per client.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_API_RATE_LIMIT", default_value = "10")]
+    pub rate: f64,
+
+    /// Maximum token bucket size, i.e. the largest burst a client can submit at once.
+    #[clap(
+        long,
+        env = "ESPRESSO_SEQUENCER_API_RATE_LIMIT_BURST",
+        default_value = "50"
+    )]
+    pub burst: f64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            rate: 10.,
+            burst: 50.,
+        }
+    }
+}
+
+/// Returns `Ok(None)` if `D::create` reports that it only ran migrations and didn't produce a
+/// data source to serve (the SQL-backed `--migrations-only` flag), propagated from [`Options::serve`].
 async fn init_with_query_module<N, D>(
     opt: Options,
     mod_opt: D::Options,
+    accounting_storage: Option<persistence::sql::Options>,
+    reset: bool,
     init_handle: impl FnOnce(Box<dyn Metrics>) -> BoxFuture<'static, (Consensus<N>, NodeIndex)>,
-) -> anyhow::Result<SequencerNode<N>>
+) -> anyhow::Result<Option<SequencerNode<N>>>
 where
     N: network::Type,
     D: SequencerDataSource + Send + Sync + 'static,
 {
     type State<N, D> = Arc<RwLock<AppState<N, D>>>;
 
-    let ds = D::create(mod_opt, false).await?;
-    let metrics = ds.populate_metrics();
+    let Some(ds) = D::create(mod_opt, reset).await? else {
+        return Ok(None);
+    };
+    let prometheus_registry = opt.prometheus.is_some().then(metrics::PrometheusMetrics::default);
+    let metrics: Box<dyn Metrics> = match &prometheus_registry {
+        Some(registry) => Box::new(registry.clone()),
+        None => ds.populate_metrics(),
+    };
 
     // Start up handle
     let (mut handle, node_index) = init_handle(metrics).await;
@@ -196,9 +392,31 @@ where
     let state: State<N, D> = Arc::new(RwLock::new(ExtensibleDataSource::new(ds, handle.clone())));
     let mut app = App::<_, Error>::with_state(state.clone());
 
+    let (shutdown, shutdown_rx) = oneshot::channel();
+    let shutdown_rx = shutdown_rx.shared();
+
     // Initialize submit API
+    let mut rate_limit_task = None;
     if opt.submit.is_some() {
-        let submit_api = endpoints::submit::<N, State<N, D>>()?;
+        let mut submit_api = endpoints::submit::<N, State<N, D>>()?;
+        if let Some(rate_limit) = &opt.rate_limit {
+            let limiter = RateLimiter::new(rate_limit.rate, rate_limit.burst);
+            if let Some(storage) = accounting_storage.clone() {
+                rate_limit_task = Some(limiter.clone().spawn_flush_task(
+                    storage,
+                    std::time::Duration::from_secs(60),
+                    shutdown_rx.clone(),
+                ));
+            } else {
+                // No SQL storage to flush accounting into, but idle buckets still need evicting
+                // to bound memory.
+                rate_limit_task = Some(limiter.clone().spawn_eviction_task(
+                    std::time::Duration::from_secs(60),
+                    shutdown_rx.clone(),
+                ));
+            }
+            submit_api.with_middleware(RateLimitMiddleware::new(limiter));
+        }
         app.register_module("submit", submit_api)?;
     }
 
@@ -212,18 +430,59 @@ where
     let availability_api = endpoints::availability::<N, D>()?;
     app.register_module("availability", availability_api)?;
 
+    // Initialize Prometheus metrics API.
+    if let Some(registry) = prometheus_registry {
+        let metrics_api = metrics::define_api(registry)?;
+        app.register_module("metrics", metrics_api)?;
+    }
+
+    // Initialize GraphQL query API.
+    if opt.graphql.is_some() {
+        let graphql_api = graphql::define_api::<N, D, State<N, D>>(state.clone())?;
+        app.register_module("graphql", graphql_api)?;
+    }
+
+    let http = opt.http.clone();
     let update_task = spawn(async move {
-        futures::join!(
-            app.serve(format!("0.0.0.0:{}", opt.http.port))
-                .map_err(anyhow::Error::from),
-            update_loop(state, events),
-        )
-        .0
+        let (serve_res, ()) = futures::join!(
+            http.bind(app, shutdown_rx.clone()),
+            update_loop(state, events, shutdown_rx),
+        );
+        serve_res
     });
 
-    Ok(SequencerNode {
+    Ok(Some(SequencerNode {
         handle,
         node_index,
         update_task,
-    })
+        rate_limit_task,
+        shutdown,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn http(tls_cert: Option<PathBuf>, tls_key: Option<PathBuf>) -> Http {
+        Http {
+            port: 0,
+            bind_address: "127.0.0.1".parse().unwrap(),
+            tls_cert,
+            tls_key,
+        }
+    }
+
+    #[async_std::test]
+    async fn bind_rejects_half_configured_tls() {
+        let app = App::<(), tide_disco::Error>::with_state(());
+        let (_shutdown, shutdown_rx) = oneshot::channel();
+        let shutdown_rx = shutdown_rx.shared();
+
+        let err = http(Some(PathBuf::from("cert.pem")), None)
+            .bind(app, shutdown_rx)
+            .await
+            .expect_err("only tls_cert set should be rejected");
+        assert!(err.to_string().contains("tls-cert"));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,309 @@
+//! Token-bucket rate limiting and per-client usage accounting for the submit API.
+
+use async_std::sync::{Arc, Mutex};
+use async_std::task::{spawn, JoinHandle};
+use futures::{select, FutureExt};
+use std::collections::HashMap;
+use std::mem;
+use std::time::{Duration, Instant};
+use tide_disco::{http::StatusCode, method::ReadState, Middleware, Next, Request};
+
+use super::node::ShutdownReceiver;
+use crate::persistence;
+
+/// How long a client's bucket may sit idle before it is evicted, bounding memory growth from
+/// clients that submitted once and never came back.
+const BUCKET_TTL: Duration = Duration::from_secs(3600);
+
+/// A client identity used to key rate-limit buckets and accounting rows.
+///
+/// Derived from the `X-Api-Key` header if present, falling back to the peer IP address.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ClientKey(String);
+
+impl ClientKey {
+    fn from_request<State>(req: &Request<State>) -> Self {
+        if let Some(key) = req.header("X-Api-Key").and_then(|v| v.get(0)) {
+            Self(format!("key:{key}"))
+        } else {
+            Self(format!("ip:{}", req.peer_addr().unwrap_or("unknown")))
+        }
+    }
+}
+
+/// A token bucket tracking remaining request allowance for a single client.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn new(burst: f64) -> Self {
+        Self {
+            tokens: burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Refill according to elapsed time, then try to take one token.
+    fn take(&mut self, rate: f64, burst: f64) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Seconds until at least one token will be available.
+    fn retry_after(&self, rate: f64) -> u64 {
+        if rate <= 0.0 {
+            return 1;
+        }
+        ((1.0 - self.tokens) / rate).ceil().max(0.0) as u64
+    }
+}
+
+/// Per-client counters tracked alongside the rate limiter.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClientStats {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub bytes_submitted: u64,
+}
+
+#[derive(Debug)]
+struct Inner {
+    buckets: HashMap<ClientKey, Bucket>,
+    /// Counters accumulated since the last flush. Drained (not just read) by each flush, so
+    /// `flush_accounting`'s upsert only ever adds each request's outcome to the database once.
+    pending: HashMap<ClientKey, ClientStats>,
+}
+
+/// Token-bucket rate limiter and usage accountant, shared across submit requests.
+///
+/// `rate` is expressed in tokens/sec and `burst` is the bucket capacity; a client is allowed a
+/// request whenever its bucket holds at least one token.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            inner: Arc::new(Mutex::new(Inner {
+                buckets: HashMap::new(),
+                pending: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Try to admit a request for `key`, recording the outcome in the pending accounting counters.
+    async fn admit(&self, key: ClientKey, bytes: u64) -> Result<(), u64> {
+        let mut inner = self.inner.lock().await;
+        let bucket = inner
+            .buckets
+            .entry(key.clone())
+            .or_insert_with(|| Bucket::new(self.burst));
+        let allowed = bucket.take(self.rate, self.burst);
+        let retry_after = bucket.retry_after(self.rate);
+
+        let stats = inner.pending.entry(key).or_default();
+        if allowed {
+            stats.accepted += 1;
+            stats.bytes_submitted += bytes;
+            Ok(())
+        } else {
+            stats.rejected += 1;
+            Err(retry_after)
+        }
+    }
+
+    /// Drain the counters accumulated since the last call, evicting any client buckets that have
+    /// been idle longer than [`BUCKET_TTL`] along the way.
+    async fn take_pending(&self) -> HashMap<ClientKey, ClientStats> {
+        let mut inner = self.inner.lock().await;
+        let now = Instant::now();
+        inner
+            .buckets
+            .retain(|_, bucket| now.duration_since(bucket.last_refill) < BUCKET_TTL);
+        mem::take(&mut inner.pending)
+    }
+
+    /// Merge counters back in, e.g. after a failed flush, so they are retried on the next one.
+    async fn restore_pending(&self, stats: HashMap<ClientKey, ClientStats>) {
+        let mut inner = self.inner.lock().await;
+        for (key, stats) in stats {
+            let entry = inner.pending.entry(key).or_default();
+            entry.accepted += stats.accepted;
+            entry.rejected += stats.rejected;
+            entry.bytes_submitted += stats.bytes_submitted;
+        }
+    }
+
+    /// Spawn a background task that periodically flushes accounting rows into the
+    /// `submit_accounting` table, stopping as soon as `shutdown` fires.
+    ///
+    /// Flushes once more before returning, bounding what a shutdown can lose to the deltas from
+    /// requests admitted after that final flush started.
+    pub fn spawn_flush_task(
+        self,
+        storage: persistence::sql::Options,
+        period: Duration,
+        mut shutdown: ShutdownReceiver,
+    ) -> JoinHandle<()> {
+        spawn(async move {
+            loop {
+                select! {
+                    _ = async_std::task::sleep(period).fuse() => (),
+                    _ = shutdown => break,
+                }
+                let pending = self.take_pending().await;
+                if pending.is_empty() {
+                    continue;
+                }
+                if let Err(err) = flush_accounting(&storage, &pending).await {
+                    tracing::warn!("failed to flush submit accounting: {err:#}");
+                    self.restore_pending(pending).await;
+                }
+            }
+
+            let pending = self.take_pending().await;
+            if !pending.is_empty() {
+                if let Err(err) = flush_accounting(&storage, &pending).await {
+                    tracing::warn!("failed to flush submit accounting on shutdown: {err:#}");
+                }
+            }
+        })
+    }
+
+    /// Spawn a background task that periodically evicts idle client buckets, stopping as soon as
+    /// `shutdown` fires.
+    ///
+    /// Used in place of [`spawn_flush_task`](Self::spawn_flush_task) when there's no SQL storage
+    /// to flush accounting into (the fs-backed and status-only server configurations), so
+    /// [`BUCKET_TTL`] eviction still runs and bounds the bucket map's memory even though the
+    /// accounting deltas themselves are simply dropped.
+    pub fn spawn_eviction_task(self, period: Duration, mut shutdown: ShutdownReceiver) -> JoinHandle<()> {
+        spawn(async move {
+            loop {
+                select! {
+                    _ = async_std::task::sleep(period).fuse() => (),
+                    _ = shutdown => break,
+                }
+                self.take_pending().await;
+            }
+        })
+    }
+}
+
+/// Upsert aggregated per-key counters into the `submit_accounting` table.
+async fn flush_accounting(
+    storage: &persistence::sql::Options,
+    stats: &HashMap<ClientKey, ClientStats>,
+) -> anyhow::Result<()> {
+    let mut conn = storage.connect().await?;
+    for (key, stats) in stats {
+        conn.execute(
+            "INSERT INTO submit_accounting (client_key, accepted, rejected, bytes_submitted) \
+             VALUES ($1, $2, $3, $4) \
+             ON CONFLICT (client_key) DO UPDATE SET \
+             accepted = submit_accounting.accepted + excluded.accepted, \
+             rejected = submit_accounting.rejected + excluded.rejected, \
+             bytes_submitted = submit_accounting.bytes_submitted + excluded.bytes_submitted",
+            &[&key.0, &(stats.accepted as i64), &(stats.rejected as i64), &(stats.bytes_submitted as i64)],
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// `tide_disco` middleware enforcing the token-bucket limit ahead of the submit module.
+#[derive(Clone, Debug)]
+pub struct RateLimitMiddleware {
+    limiter: RateLimiter,
+}
+
+impl RateLimitMiddleware {
+    pub fn new(limiter: RateLimiter) -> Self {
+        Self { limiter }
+    }
+}
+
+#[async_trait::async_trait]
+impl<State: ReadState + Send + Sync + 'static> Middleware<State> for RateLimitMiddleware {
+    async fn handle(
+        &self,
+        req: Request<State>,
+        next: Next<'_, State>,
+    ) -> Result<tide_disco::Response, tide_disco::Error> {
+        let key = ClientKey::from_request(&req);
+        let bytes = req.len().unwrap_or(0) as u64;
+
+        match self.limiter.admit(key, bytes).await {
+            Ok(()) => Ok(next.run(req).await?),
+            Err(retry_after) => {
+                let mut res = tide_disco::Response::new(StatusCode::TooManyRequests as u16);
+                res.insert_header("Retry-After", retry_after.to_string());
+                Ok(res)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_burst_then_exhausts() {
+        let mut bucket = Bucket::new(2.0);
+        assert!(bucket.take(1.0, 2.0));
+        assert!(bucket.take(1.0, 2.0));
+        // Burst is exhausted and no time has passed to refill it.
+        assert!(!bucket.take(1.0, 2.0));
+    }
+
+    #[async_std::test]
+    async fn admit_accepts_within_burst_and_rejects_beyond_it() {
+        let limiter = RateLimiter::new(1.0, 2.0);
+        let key = ClientKey("test".into());
+
+        assert!(limiter.admit(key.clone(), 10).await.is_ok());
+        assert!(limiter.admit(key.clone(), 10).await.is_ok());
+        assert!(limiter.admit(key.clone(), 10).await.is_err());
+
+        let pending = limiter.take_pending().await;
+        let stats = pending.get(&key).unwrap();
+        assert_eq!(stats.accepted, 2);
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(stats.bytes_submitted, 20);
+    }
+
+    #[async_std::test]
+    async fn take_pending_evicts_idle_buckets() {
+        let limiter = RateLimiter::new(1.0, 2.0);
+        let key = ClientKey("idle".into());
+        limiter.admit(key.clone(), 0).await.ok();
+
+        {
+            let mut inner = limiter.inner.lock().await;
+            let bucket = inner.buckets.get_mut(&key).unwrap();
+            bucket.last_refill = Instant::now() - BUCKET_TTL - Duration::from_secs(1);
+        }
+
+        limiter.take_pending().await;
+        assert!(!limiter.inner.lock().await.buckets.contains_key(&key));
+    }
+}
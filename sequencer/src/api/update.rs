@@ -0,0 +1,46 @@
+//! The event-consumption loop that keeps the query API's data source in sync with consensus.
+
+use async_std::sync::{Arc, RwLock};
+use futures::{select, Stream, StreamExt};
+use hotshot::types::Event;
+use hotshot_query_service::data_source::{UpdateDataSource, VersionedDataSource};
+
+use super::{node::ShutdownReceiver, AppState};
+use crate::network;
+
+/// Apply consensus events to `state`'s data source as they arrive, until `shutdown` fires or the
+/// event stream ends.
+///
+/// `update` only stages an event's effects in memory; each one is followed by a `commit` so the
+/// write actually lands in storage before the next event (or a shutdown) is considered. That
+/// ordering is what lets at most one event's worth of writes be outstanding when `shutdown`
+/// fires, which is what [`SequencerNode::shutdown`](super::node::SequencerNode::shutdown)'s
+/// "flush pending SQL writes" guarantee relies on: `select!` only breaks the loop between
+/// iterations, never inside one, so a commit that's already started always finishes.
+pub(super) async fn update_loop<N, D>(
+    state: Arc<RwLock<AppState<N, D>>>,
+    mut events: impl Stream<Item = Event<N::Types>> + Unpin + Send,
+    mut shutdown: ShutdownReceiver,
+) where
+    N: network::Type,
+    D: UpdateDataSource<N::Types> + VersionedDataSource + Send + Sync + 'static,
+{
+    loop {
+        select! {
+            event = events.next() => {
+                let Some(event) = event else {
+                    break;
+                };
+                let mut state = state.write().await;
+                if let Err(err) = state.update(&event).await {
+                    tracing::warn!("failed to update query state: {err:#}");
+                    continue;
+                }
+                if let Err(err) = state.commit().await {
+                    tracing::warn!("failed to flush query state to storage: {err:#}");
+                }
+            }
+            _ = shutdown => break,
+        }
+    }
+}
@@ -0,0 +1,11 @@
+//! File system storage for the availability data source.
+
+use std::path::PathBuf;
+
+/// Options for file system storage.
+#[derive(clap::Parser, Clone, Debug)]
+pub struct Options {
+    /// Storage path for the file system data source.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_STORAGE_PATH")]
+    pub path: PathBuf,
+}
@@ -0,0 +1,4 @@
+//! Persistent storage backends for the availability data source.
+
+pub mod fs;
+pub mod sql;
@@ -0,0 +1,187 @@
+//! Postgres storage for the availability data source, with versioned schema migrations.
+
+use std::path::PathBuf;
+
+use refinery::Migration;
+
+/// Options for Postgres storage.
+#[derive(clap::Parser, Clone, Debug)]
+pub struct Options {
+    /// Postgres connection string or URI.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_POSTGRES_URI")]
+    pub uri: String,
+
+    /// Only run pending migrations, then exit, instead of starting the server.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_POSTGRES_MIGRATIONS_ONLY")]
+    pub migrations_only: bool,
+
+    /// Shell command to run before applying any migration, so operators can roll back.
+    ///
+    /// The command is run with `sh -c` and is expected to produce a restorable backup (e.g. a
+    /// `pg_dump` invocation); migration is aborted if it exits non-zero.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_POSTGRES_BACKUP_COMMAND")]
+    pub backup_command: Option<String>,
+
+    /// Drop and recreate the entire `public` schema before migrating, rebuilding the schema from
+    /// scratch instead of migrating forward.
+    ///
+    /// Destructive; meant for recovering from a divergent or corrupt schema, not routine use.
+    #[clap(long, env = "ESPRESSO_SEQUENCER_POSTGRES_RESET_STORE")]
+    pub reset_store: bool,
+}
+
+/// Name of the table used to track which migrations have been applied.
+const MIGRATIONS_TABLE: &str = "applied_migrations";
+
+impl Options {
+    /// Open a connection to the configured database.
+    pub async fn connect(&self) -> anyhow::Result<tokio_postgres::Client> {
+        let (client, connection) =
+            tokio_postgres::connect(&self.uri, tokio_postgres::NoTls).await?;
+        async_std::task::spawn(async move {
+            if let Err(err) = connection.await {
+                tracing::error!("postgres connection error: {err:#}");
+            }
+        });
+        Ok(client)
+    }
+
+    /// Create the data source, applying any pending migrations first.
+    ///
+    /// Compares the embedded migration set against the `applied_migrations` tracking table and
+    /// applies whatever is missing, transactionally. Refuses to start if a migration recorded as
+    /// applied in the tracking table is not present in the embedded set (schema divergence between
+    /// this binary and the database).
+    ///
+    /// If `reset` is set, the entire `public` schema is dropped and recreated before migrating.
+    ///
+    /// Returns [`Created::MigrationsOnly`] if `migrations_only` is set: migrations are applied,
+    /// but no data source is returned to serve, and the caller decides whether and how to exit.
+    pub async fn create(self, reset: bool) -> anyhow::Result<Created> {
+        let mut client = self.connect().await?;
+
+        if reset {
+            tracing::warn!("--reset-store: dropping existing sequencer tables");
+            reset_tables(&client).await?;
+        }
+
+        self.migrate(&mut client).await?;
+
+        if self.migrations_only {
+            tracing::info!("migrations applied; exiting as requested by --migrations-only");
+            return Ok(Created::MigrationsOnly);
+        }
+
+        Ok(Created::Ready(DataSource { client }))
+    }
+
+    /// Apply all pending migrations, backing up first if `backup_command` is set.
+    async fn migrate(&self, client: &mut tokio_postgres::Client) -> anyhow::Result<()> {
+        ensure_migrations_table(client).await?;
+
+        let embedded = embedded_migrations();
+        let applied = applied_migrations(client).await?;
+
+        // Refuse to start if the database has a migration we don't know about locally: the
+        // binary is older than the schema it's pointed at.
+        for name in &applied {
+            if !embedded.iter().any(|m| &m.name() == name) {
+                anyhow::bail!(
+                    "database has migration `{name}` applied that is not present in this \
+                     binary; refusing to start with a divergent schema"
+                );
+            }
+        }
+
+        let pending: Vec<_> = embedded
+            .into_iter()
+            .filter(|m| !applied.contains(&m.name().to_string()))
+            .collect();
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(command) = &self.backup_command {
+            run_backup(command).await?;
+        }
+
+        for migration in pending {
+            let tx = client.transaction().await?;
+            tx.batch_execute(migration.sql().unwrap_or_default())
+                .await?;
+            tx.execute(
+                &format!("INSERT INTO {MIGRATIONS_TABLE} (name) VALUES ($1)"),
+                &[&migration.name()],
+            )
+            .await?;
+            tx.commit().await?;
+            tracing::info!("applied migration {}", migration.name());
+        }
+
+        Ok(())
+    }
+}
+
+/// Drop the whole `public` schema and recreate it empty, so `migrate` starts from scratch.
+///
+/// Dropping the schema wholesale means this never needs updating as `embedded_migrations()`
+/// grows new tables.
+async fn reset_tables(client: &tokio_postgres::Client) -> anyhow::Result<()> {
+    client
+        .batch_execute("DROP SCHEMA public CASCADE; CREATE SCHEMA public;")
+        .await?;
+    Ok(())
+}
+
+async fn ensure_migrations_table(client: &tokio_postgres::Client) -> anyhow::Result<()> {
+    client
+        .batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {MIGRATIONS_TABLE} (
+                name TEXT PRIMARY KEY,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )"
+        ))
+        .await?;
+    Ok(())
+}
+
+async fn applied_migrations(client: &tokio_postgres::Client) -> anyhow::Result<Vec<String>> {
+    let rows = client
+        .query(&format!("SELECT name FROM {MIGRATIONS_TABLE}"), &[])
+        .await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Run the configured backup command, failing the migration if it exits non-zero.
+async fn run_backup(command: &str) -> anyhow::Result<()> {
+    tracing::info!("running pre-migration backup: {command}");
+    let status = async_std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .status()
+        .await?;
+    if !status.success() {
+        anyhow::bail!("backup command failed with {status}");
+    }
+    Ok(())
+}
+
+/// The numbered migrations embedded in this binary, in order.
+fn embedded_migrations() -> Vec<Migration> {
+    refinery::embed_migrations!("migrations").runner().migrations()
+}
+
+/// The Postgres-backed availability data source.
+pub struct DataSource {
+    client: tokio_postgres::Client,
+}
+
+/// The outcome of [`Options::create`].
+pub enum Created {
+    /// Migrations are applied and the data source is ready to serve.
+    Ready(DataSource),
+    /// `--migrations-only` was set: migrations are applied, and it's up to the caller whether
+    /// and how to exit.
+    MigrationsOnly,
+}